@@ -0,0 +1,227 @@
+//! Compiling and running `build.rs` build scripts.
+//!
+//! A build script is just a `bin` crate we compile and run like any other,
+//! except its stdout matters: lines like `cargo:rustc-cfg=...` have to be
+//! translated into flags and environment variables for the *dependent*
+//! crate's own `rustc` invocation. Ninja has no way to make that decision
+//! while generating the build file, since it depends on what the script
+//! prints at run time -- so `create` only wires up the two steps, and the
+//! actual translation happens at build time via the awk script in
+//! [`TRANSLATOR_AWK`].
+
+use std::{
+    collections::BTreeSet,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use cargo_toml::{Manifest, OptionalFile};
+
+use crate::platform::Target;
+use crate::profile::Profile;
+
+/// Extra ninja rules this module needs, appended after [`crate::DEFAULT_RULES`].
+pub(crate) const RULES: &str = r#"rule build-script-run
+  command = mkdir -p $outdir && : > $argsfile && printf 'OUT_DIR=%s\n' $outdir > $envfile && cd $workdir && env CARGO_MANIFEST_DIR=$workdir OUT_DIR=$outdir TARGET=$target HOST=$host NUM_JOBS=1 $cfgenv $featureenv $bin | awk -v argsfile=$argsfile -v envfile=$envfile -f $translator && touch $out
+  description = RUN $name build script
+"#;
+
+/// The awk program that turns a build script's captured stdout into an
+/// rustc-flags file and an rustc-env file. Written out to disk once, next
+/// to the generated ninja file, rather than inlined into the rule command.
+pub(crate) const TRANSLATOR_AWK: &str = r#"# Translates a build script's `cargo:` directives (read on stdin) into an
+# rustc-flags file (argsfile) and an rustc-env file (envfile). Link/search
+# metadata and `rustc-flags` pass straight through; `links` metadata meant
+# for other build scripts isn't tracked since nothing downstream reads it.
+{
+    line = $0
+    if (sub(/^cargo:rustc-cfg=/, "", line)) {
+        printf "--cfg %s ", line >> argsfile
+    } else if (sub(/^cargo:rustc-link-lib=/, "", line)) {
+        printf "-l %s ", line >> argsfile
+    } else if (sub(/^cargo:rustc-link-search=/, "", line)) {
+        printf "-L %s ", line >> argsfile
+    } else if (sub(/^cargo:rustc-flags=/, "", line)) {
+        printf "%s ", line >> argsfile
+    } else if (sub(/^cargo:rustc-env=/, "", line)) {
+        print line >> envfile
+    }
+}
+"#;
+
+/// Files a build script step hands back for wiring into the dependent
+/// crate's own `build_rule` call.
+pub(crate) struct Outputs {
+    /// Order-only dependency: the crate's own compile must wait for this.
+    pub(crate) stamp: String,
+    /// `$(cat ...)` into the crate's `args`, for flags the script asked for.
+    pub(crate) args_file: String,
+    /// `$(cat ...)` sourced via `env` before the crate's own `rustc` call.
+    pub(crate) env_file: String,
+}
+
+/// Find `package.build`'s script, honouring an explicit path, `build = false`,
+/// and the default `build.rs`-if-present convention.
+pub(crate) fn detect(manifest: &Manifest, crate_dir: &Path) -> Option<PathBuf> {
+    let package = manifest.package.as_ref()?;
+    match package.build.as_ref() {
+        Some(OptionalFile::Path(path)) => Some(crate_dir.join(path)),
+        Some(OptionalFile::Flag(false)) => None,
+        Some(OptionalFile::Flag(true)) | None => {
+            let default = crate_dir.join("build.rs");
+            default.is_file().then_some(default)
+        }
+    }
+}
+
+/// Emit the `build-script-build` compile step and the `build-script-run`
+/// step for one crate, and return the files the crate's own `build_rule`
+/// needs to depend on and read from.
+///
+/// The build script binary and its `[build-dependencies]` always run on
+/// the host doing the build, never on `--target`, so `build_deps_dir` must
+/// point at the host-compiled `deps` directory even when `target` (used
+/// only for the `TARGET`/`HOST`/`CARGO_CFG_*` env vars the script itself
+/// sees) is some other cross-compilation triple.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn emit<W: Write>(
+    mut out: W,
+    norm_pkg_name: &str,
+    script: &Path,
+    crate_dir: &Path,
+    build_deps: &[&str],
+    edition: &str,
+    target: &Target,
+    build_deps_dir: &str,
+    features: &BTreeSet<String>,
+    translator: &Path,
+    profile: &Profile,
+) -> io::Result<Outputs> {
+    // Everything below gets a `cd $workdir` between it and the build script
+    // actually running, so these all need to be absolute.
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let build_dir = cwd.join(format!("build/{}/build-{}", profile.out_dir, norm_pkg_name));
+    let bin = build_dir.join("build_script_build");
+    let out_dir = build_dir.join("out");
+    let args_file = build_dir.join("rustc-flags");
+    let env_file = build_dir.join("env");
+    let stamp = build_dir.join("invoked.stamp");
+    let workdir = fs::canonicalize(crate_dir).unwrap_or_else(|_| crate_dir.to_path_buf());
+
+    // Compiling a build script is nothing special: it's a `bin` crate built
+    // from its own `[build-dependencies]`, for the host.
+    write!(out, "build {}: rustc {} | ", bin.display(), script.display())?;
+    for dep in build_deps {
+        write!(
+            out,
+            "{}/lib{}.rlib ",
+            build_deps_dir,
+            crate::normalize_crate_name(dep)
+        )?;
+    }
+    writeln!(out)?;
+    writeln!(out, "  name = build_script_build")?;
+    write!(
+        out,
+        "  args = --crate-type bin --edition {} -L dependency={} ",
+        edition, build_deps_dir
+    )?;
+    for dep in build_deps {
+        let norm = crate::normalize_crate_name(dep);
+        write!(out, "--extern {}={}/lib{}.rlib ", norm, build_deps_dir, norm)?;
+    }
+    writeln!(out)?;
+    writeln!(out, "  outdir = {}", build_dir.display())?;
+    writeln!(out, "  emit = link")?;
+    writeln!(out, "  depfile = {}/build_script_build.d", build_dir.display())?;
+    writeln!(out, "  envfile = /dev/null")?;
+    writeln!(out)?;
+
+    // Run it, capturing the `cargo:` directives it prints into the files
+    // the dependent crate's own compile step will read back.
+    writeln!(out, "build {}: build-script-run {}", stamp.display(), bin.display())?;
+    writeln!(out, "  name = {}", norm_pkg_name)?;
+    writeln!(out, "  bin = {}", bin.display())?;
+    writeln!(out, "  workdir = {}", workdir.display())?;
+    writeln!(out, "  outdir = {}", out_dir.display())?;
+    writeln!(out, "  argsfile = {}", args_file.display())?;
+    writeln!(out, "  envfile = {}", env_file.display())?;
+    writeln!(out, "  translator = {}", translator.display())?;
+    writeln!(out, "  target = {}", target.triple)?;
+    writeln!(out, "  host = {}", target.host_triple())?;
+    writeln!(out, "  cfgenv = {}", target.cargo_cfg_env())?;
+    writeln!(out, "  featureenv = {}", feature_env(features))?;
+    writeln!(out)?;
+
+    Ok(Outputs {
+        stamp: stamp.display().to_string(),
+        args_file: args_file.display().to_string(),
+        env_file: env_file.display().to_string(),
+    })
+}
+
+/// Write the awk translator script next to the generated ninja file.
+pub(crate) fn write_translator(ninja_file: &Path) -> io::Result<PathBuf> {
+    let path = ninja_file
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."))
+        .join("bygge-build-script-translate.awk");
+    fs::write(&path, TRANSLATOR_AWK)?;
+    Ok(fs::canonicalize(&path).unwrap_or(path))
+}
+
+/// `CARGO_FEATURE_<NAME>=1` for every enabled feature, as Cargo sets them
+/// for build scripts.
+fn feature_env(features: &BTreeSet<String>) -> String {
+    features
+        .iter()
+        .map(|f| format!("CARGO_FEATURE_{}=1", f.to_uppercase().replace('-', "_")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host_target() -> Target {
+        Target::detect(None).unwrap()
+    }
+
+    /// Regression test for the build-script-build step linking against
+    /// whatever `deps_dir` the *crate under build* happened to use: under
+    /// `--target`, that's the cross-compiled `deps` dir, so a build script's
+    /// own `[build-dependencies]` rlibs (always compiled for the host)
+    /// wouldn't match it at link time. `emit` must use `build_deps_dir`
+    /// (the host one) for both the compile command and its `--extern` args,
+    /// regardless of what `target` it's generating `build-script-run`'s env
+    /// for.
+    #[test]
+    fn emit_links_build_script_against_host_deps_dir() {
+        let target = host_target();
+        let profile = Profile::resolve("dev", &Manifest::from_slice(b"").unwrap());
+        let mut out = Vec::new();
+
+        emit(
+            &mut out,
+            "bindgen_helper",
+            Path::new("/crate/build.rs"),
+            Path::new("/crate"),
+            &["cc"],
+            "2018",
+            &target,
+            "build/debug/host-deps",
+            &BTreeSet::new(),
+            Path::new("/build/bygge-build-script-translate.awk"),
+            &profile,
+        )
+        .unwrap();
+
+        let ninja = String::from_utf8(out).unwrap();
+        assert!(ninja.contains("-L dependency=build/debug/host-deps "));
+        assert!(ninja.contains("--extern cc=build/debug/host-deps/libcc.rlib "));
+        assert!(!ninja.contains("dependency=build/debug/deps"));
+    }
+}
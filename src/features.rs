@@ -0,0 +1,378 @@
+//! Cargo feature resolution.
+//!
+//! Cargo computes, for every package in the dependency graph, the set of
+//! enabled features by unioning together every feature requested along
+//! every path that reaches that package (a crate is only ever compiled
+//! once, so all of its activations have to agree on one feature set).
+//! This module does the same thing over the manifests `create` has
+//! already loaded, so `build_rule` can emit the right
+//! `--cfg 'feature="..."'` flags and only `--extern` the optional
+//! dependencies that actually got turned on.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+
+use cargo_toml::{Dependency, DepsSet, Manifest};
+
+use crate::platform::Target;
+
+/// The features and activated optional dependencies resolved for one
+/// package, keyed by crate name.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Resolved {
+    pub(crate) features: BTreeSet<String>,
+    pub(crate) active_deps: BTreeSet<String>,
+    /// Crates named in `[build-dependencies]`, needed to compile this
+    /// package's own `build.rs`. These never feed into the package's own
+    /// `--extern` list.
+    pub(crate) build_deps: BTreeSet<String>,
+}
+
+/// Feature table and dependency metadata pulled out of a single manifest.
+#[derive(Debug, Default)]
+struct CrateInfo {
+    features: BTreeMap<String, Vec<String>>,
+    // Keyed by the dependency's table key, which may differ from its
+    // crate name when `package = "..."` is used.
+    deps: BTreeMap<String, DepMeta>,
+    // `[build-dependencies]`. Kept separate from `deps` since they're only
+    // needed to compile `build.rs`, not the crate itself; we don't bother
+    // resolving features for them.
+    build_deps: BTreeMap<String, DepMeta>,
+}
+
+#[derive(Debug, Clone)]
+struct DepMeta {
+    crate_name: String,
+    optional: bool,
+    default_features: bool,
+    features: Vec<String>,
+}
+
+impl CrateInfo {
+    fn from_manifest(manifest: &Manifest, target: &Target) -> CrateInfo {
+        let mut deps = BTreeMap::new();
+        collect_deps(&manifest.dependencies, &mut deps);
+        for (predicate, platform_deps) in &manifest.target {
+            if target.matches(predicate) {
+                collect_deps(&platform_deps.dependencies, &mut deps);
+            }
+        }
+
+        let mut build_deps = BTreeMap::new();
+        collect_deps(&manifest.build_dependencies, &mut build_deps);
+        for (predicate, platform_deps) in &manifest.target {
+            if target.matches(predicate) {
+                collect_deps(&platform_deps.build_dependencies, &mut build_deps);
+            }
+        }
+
+        CrateInfo {
+            features: manifest.features.clone(),
+            deps,
+            build_deps,
+        }
+    }
+}
+
+fn collect_deps(deps: &DepsSet, out: &mut BTreeMap<String, DepMeta>) {
+    for (key, dep) in deps {
+        let (optional, default_features, features, package) = match dep {
+            Dependency::Simple(_) => (false, true, Vec::new(), None),
+            Dependency::Detailed(detail) => (
+                detail.optional,
+                detail.default_features,
+                detail.features.clone(),
+                detail.package.clone(),
+            ),
+            // Inherited from `[workspace.dependencies]`; we don't have the
+            // workspace manifest handy to resolve it, so treat it as a
+            // plain, always-active dependency.
+            Dependency::Inherited(_) => (false, true, Vec::new(), None),
+        };
+        out.insert(
+            key.clone(),
+            DepMeta {
+                crate_name: package.unwrap_or_else(|| key.clone()),
+                optional,
+                default_features,
+                features,
+            },
+        );
+    }
+}
+
+/// Resolve the enabled features (and activated optional dependencies) for
+/// every crate name in `manifests`, starting from the root package.
+///
+/// `cli_features`, `no_default_features` and `all_features` mirror
+/// `cargo build`'s `--features` / `--no-default-features` / `--all-features`
+/// flags and only apply to the root package.
+pub(crate) fn resolve(
+    root_name: &str,
+    manifests: &HashMap<String, Manifest>,
+    target: &Target,
+    cli_features: &[String],
+    no_default_features: bool,
+    all_features: bool,
+) -> HashMap<String, Resolved> {
+    let infos: HashMap<String, CrateInfo> = manifests
+        .iter()
+        .map(|(name, manifest)| (name.clone(), CrateInfo::from_manifest(manifest, target)))
+        .collect();
+
+    let mut resolved: HashMap<String, Resolved> = HashMap::new();
+    let mut queue: VecDeque<(String, String)> = VecDeque::new();
+    // Weak (`name?/feature`) requests seen before `name` was activated by
+    // something else, keyed by `(owner, dependency crate name)`. Drained
+    // by `activate_dep` the moment that dependency actually turns on, so
+    // a later-processed feature activating the same optional dependency
+    // still forwards any feature requests an earlier one made.
+    let mut pending_weak: HashMap<(String, String), Vec<String>> = HashMap::new();
+
+    // Every non-optional dependency is active no matter which features end
+    // up enabled, and its own `features = [...]`/`default-features` in the
+    // depending crate's manifest still has to be honoured.
+    for (name, info) in &infos {
+        let entry = resolved.entry(name.clone()).or_default();
+        for meta in info.deps.values().filter(|m| !m.optional) {
+            entry.active_deps.insert(meta.crate_name.clone());
+        }
+        // Build-dependencies aren't gated by features here: unlike regular
+        // optional dependencies, an optional build-dependency would need
+        // its own feature wiring to activate, which is rare enough in
+        // practice that we just always pull it in.
+        for meta in info.build_deps.values() {
+            entry.build_deps.insert(meta.crate_name.clone());
+        }
+    }
+    for info in infos.values() {
+        for meta in info.deps.values().filter(|m| !m.optional) {
+            if meta.default_features {
+                queue.push_back((meta.crate_name.clone(), "default".to_string()));
+            }
+            for feature in &meta.features {
+                queue.push_back((meta.crate_name.clone(), feature.clone()));
+            }
+        }
+    }
+
+    if let Some(info) = infos.get(root_name) {
+        if all_features {
+            for feature in info.features.keys() {
+                queue.push_back((root_name.to_string(), feature.clone()));
+            }
+            for meta in info.deps.values().filter(|m| m.optional) {
+                queue.push_back((root_name.to_string(), meta.crate_name.clone()));
+            }
+        } else if !no_default_features {
+            queue.push_back((root_name.to_string(), "default".to_string()));
+        }
+    }
+    for feature in cli_features {
+        queue.push_back((root_name.to_string(), feature.clone()));
+    }
+
+    while let Some((name, feature)) = queue.pop_front() {
+        let entry = resolved.entry(name.clone()).or_default();
+        if !entry.features.insert(feature.clone()) {
+            continue;
+        }
+
+        let info = match infos.get(&name) {
+            Some(info) => info,
+            // No manifest for this crate (e.g. it was skipped earlier) --
+            // there's nothing left to expand.
+            None => continue,
+        };
+
+        if let Some(expansion) = info.features.get(&feature) {
+            for item in expansion {
+                expand(&name, item, info, &mut resolved, &mut queue, &mut pending_weak);
+            }
+            continue;
+        }
+
+        // Not declared in `[features]`: if it names an optional
+        // dependency, enabling it is exactly that dependency's implicit
+        // feature.
+        if let Some(meta) = info.deps.get(&feature).filter(|m| m.optional) {
+            activate_dep(&name, meta, &mut resolved, &mut queue, &mut pending_weak);
+        }
+    }
+
+    resolved
+}
+
+/// Expand one entry of a `[features]` list: a plain feature name, a
+/// `dep:name` activation, or a `name/feature` (optionally weak,
+/// `name?/feature`) dependency-feature request.
+fn expand(
+    owner: &str,
+    item: &str,
+    info: &CrateInfo,
+    resolved: &mut HashMap<String, Resolved>,
+    queue: &mut VecDeque<(String, String)>,
+    pending_weak: &mut HashMap<(String, String), Vec<String>>,
+) {
+    if let Some(dep_key) = item.strip_prefix("dep:") {
+        if let Some(meta) = info.deps.get(dep_key) {
+            activate_dep(owner, meta, resolved, queue, pending_weak);
+        }
+        return;
+    }
+
+    if let Some((dep_part, feature)) = item.split_once('/') {
+        let (dep_key, weak) = match dep_part.strip_suffix('?') {
+            Some(key) => (key, true),
+            None => (dep_part, false),
+        };
+        let meta = match info.deps.get(dep_key) {
+            Some(meta) => meta,
+            None => return,
+        };
+
+        if weak {
+            let active = resolved
+                .get(owner)
+                .is_some_and(|r| r.active_deps.contains(&meta.crate_name));
+            if active {
+                queue.push_back((meta.crate_name.clone(), feature.to_string()));
+            } else {
+                // Not activated yet -- stash the request so `activate_dep`
+                // can forward it the moment something else does turn this
+                // dependency on, instead of dropping it on the floor.
+                pending_weak
+                    .entry((owner.to_string(), meta.crate_name.clone()))
+                    .or_default()
+                    .push(feature.to_string());
+            }
+            return;
+        }
+
+        activate_dep(owner, meta, resolved, queue, pending_weak);
+        queue.push_back((meta.crate_name.clone(), feature.to_string()));
+        return;
+    }
+
+    queue.push_back((owner.to_string(), item.to_string()));
+}
+
+fn activate_dep(
+    owner: &str,
+    meta: &DepMeta,
+    resolved: &mut HashMap<String, Resolved>,
+    queue: &mut VecDeque<(String, String)>,
+    pending_weak: &mut HashMap<(String, String), Vec<String>>,
+) {
+    let newly_active = resolved
+        .entry(owner.to_string())
+        .or_default()
+        .active_deps
+        .insert(meta.crate_name.clone());
+    if !newly_active {
+        return;
+    }
+
+    if let Some(features) = pending_weak.remove(&(owner.to_string(), meta.crate_name.clone())) {
+        for feature in features {
+            queue.push_back((meta.crate_name.clone(), feature));
+        }
+    }
+
+    if meta.default_features {
+        queue.push_back((meta.crate_name.clone(), "default".to_string()));
+    }
+    for feature in &meta.features {
+        queue.push_back((meta.crate_name.clone(), feature.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(toml: &str) -> Manifest {
+        Manifest::from_slice(toml.as_bytes()).unwrap()
+    }
+
+    fn host_target() -> Target {
+        Target::detect(None).unwrap()
+    }
+
+    /// Regression test for a weak (`name?/feature`) request processed
+    /// before the dependency it refers to is activated: `a` is expanded
+    /// before `b` (both come from `default`), so without a fixpoint
+    /// `depx?/feat1` would be evaluated while `depx` is still inactive
+    /// and `feat1` would never reach it.
+    #[test]
+    fn weak_dependency_resolves_regardless_of_activation_order() {
+        let root = manifest(
+            r#"
+            [package]
+            name = "root"
+            version = "0.1.0"
+
+            [features]
+            default = ["a", "b"]
+            a = ["depx?/feat1"]
+            b = ["depx"]
+
+            [dependencies]
+            depx = { version = "1", optional = true }
+            "#,
+        );
+        let depx = manifest(
+            r#"
+            [package]
+            name = "depx"
+            version = "1.0.0"
+
+            [features]
+            feat1 = []
+            "#,
+        );
+
+        let mut manifests = HashMap::new();
+        manifests.insert("root".to_string(), root);
+        manifests.insert("depx".to_string(), depx);
+
+        let target = host_target();
+        let resolved = resolve("root", &manifests, &target, &[], false, false);
+
+        assert!(resolved["depx"].features.contains("feat1"));
+    }
+
+    #[test]
+    fn dep_colon_activates_the_dependency_without_its_name_as_a_feature() {
+        let root = manifest(
+            r#"
+            [package]
+            name = "root"
+            version = "0.1.0"
+
+            [features]
+            default = ["dep:opt"]
+
+            [dependencies]
+            opt = { version = "1", optional = true, default-features = false }
+            "#,
+        );
+        let opt = manifest(
+            r#"
+            [package]
+            name = "opt"
+            version = "1.0.0"
+            "#,
+        );
+
+        let mut manifests = HashMap::new();
+        manifests.insert("root".to_string(), root);
+        manifests.insert("opt".to_string(), opt);
+
+        let target = host_target();
+        let resolved = resolve("root", &manifests, &target, &[], false, false);
+
+        assert!(resolved["root"].active_deps.contains("opt"));
+        assert!(!resolved["root"].features.contains("opt"));
+    }
+}
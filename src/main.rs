@@ -1,25 +1,37 @@
+mod buildscript;
+mod features;
+mod platform;
+mod profile;
+mod source;
+mod workspace;
+
 use std::{
+    collections::{BTreeSet, HashMap, HashSet},
     convert::TryFrom,
     fmt,
     fs::File,
     io::{Read, Write},
-    path::Path,
+    path::{Path, PathBuf},
     process::{self, Command},
 };
 
-use cargo_lock::{Dependency, Lockfile};
+use cargo_lock::Lockfile;
 use cargo_toml::Manifest;
 use petgraph::visit::Bfs;
 
+use features::Resolved;
+use platform::Target;
+use profile::Profile;
+use workspace::Member;
+
 const DEFAULT_RULES: &str = r#"# Rules generated by bygge. DO NOT MODIFY BY HAND!
-extraargs = --cap-lints allow -C debuginfo=2
 
 rule cargo-fetch
   command = cargo fetch --manifest-path $in && touch $out
   description = CARGO $in
 
 rule rustc
-  command = rustc --crate-name $name $in --emit=$emit --out-dir $outdir $extraargs $args && sed -i '' '/\.d:/g' $depfile
+  command = env $$(cat $envfile 2>/dev/null) rustc --crate-name $name $in --emit=$emit --out-dir $outdir $extraargs $args && sed -i '' '/\.d:/g' $depfile
   description = RUSTC $out
   depfile = $depfile
   deps = gcc
@@ -27,8 +39,6 @@ rule rustc
 build Cargo.lock: cargo-fetch Cargo.toml
 "#;
 
-const REGISTRY_PATH: &str = "/Users/jrediger/.cargo/registry/src/github.com-1ecc6299db9ec823";
-
 struct Error(String);
 
 impl Error {
@@ -76,6 +86,13 @@ struct Args {
     manifest_path: String,
     lockfile: String,
     ninja_file: String,
+    features: Vec<String>,
+    no_default_features: bool,
+    all_features: bool,
+    target: Option<String>,
+    release: bool,
+    profile: Option<String>,
+    packages: Vec<String>,
     command: String,
 }
 
@@ -88,7 +105,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         version: args.contains(["-V", "--version"]),
         verbose: args.contains(["-v", "--verbose"]),
         manifest_path: args
-            .opt_value_from_str(["-p", "--manifest-path"])?
+            .opt_value_from_str("--manifest-path")?
             .unwrap_or_else(|| "Cargo.toml".into()),
         lockfile: args
             .opt_value_from_str(["-l", "--lockfile"])?
@@ -96,6 +113,19 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         ninja_file: args
             .opt_value_from_str(["-n", "--ninjafile"])?
             .unwrap_or_else(|| "build.ninja".into()),
+        features: args
+            .values_from_str::<_, String>("--features")?
+            .iter()
+            .flat_map(|list| list.split([',', ' ']))
+            .filter(|f| !f.is_empty())
+            .map(str::to_string)
+            .collect(),
+        no_default_features: args.contains("--no-default-features"),
+        all_features: args.contains("--all-features"),
+        target: args.opt_value_from_str("--target")?,
+        release: args.contains("--release"),
+        profile: args.opt_value_from_str("--profile")?,
+        packages: args.values_from_str(["-p", "--package"])?,
         command: args.subcommand()?.unwrap_or_else(|| "".into()),
     };
 
@@ -131,100 +161,429 @@ fn create(args: Args) -> Result<(), Box<dyn std::error::Error>> {
 
     let mut rules = File::create(&args.ninja_file)?;
     writeln!(rules, "{}", DEFAULT_RULES)?;
+    writeln!(rules, "{}", buildscript::RULES)?;
+    let translator = buildscript::write_translator(Path::new(&args.ninja_file))?;
 
     let lockfile = Lockfile::load(&args.lockfile)?;
+    if args.verbose {
+        println!("==> Detected {} dependencies.", lockfile.packages.len());
+    }
 
-    let manifest = Manifest::from_path(&args.manifest_path)?;
-    let package = manifest.package.unwrap();
-    let pkg_name = package.name;
-    println!("==> Package: {}", pkg_name);
+    let root_manifest = Manifest::from_path(&args.manifest_path)?;
+    let workspace_root = Path::new(&args.manifest_path)
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."))
+        .to_path_buf();
+
+    let members = workspace_members(&args.manifest_path, &workspace_root, &root_manifest)?;
+    let default_members = root_manifest
+        .workspace
+        .as_ref()
+        .map(|ws| ws.default_members.clone())
+        .unwrap_or_default();
+    let selected = workspace::select(&members, &workspace_root, &args.packages, &default_members)?;
+
+    let target = Target::detect(args.target.as_deref())?;
+    if args.verbose {
+        println!("==> Target: {}", target.triple);
+    }
 
-    let root_package = lockfile
-        .packages
-        .iter()
-        .find(|pkg| pkg.name.as_str() == pkg_name)
-        .unwrap();
+    // `build.rs` scripts, and the `[build-dependencies]` compiled to run
+    // them, always execute on the machine doing the build, never on
+    // `--target`: only detect (and build into) a separate host target when
+    // we're actually cross-compiling, so the common non-cross case doesn't
+    // pay for a second `rustc --print cfg` or a second `deps` directory.
+    let host_target = if target.is_host() {
+        None
+    } else {
+        Some(Target::detect(None)?)
+    };
 
+    let profile_name = args
+        .profile
+        .clone()
+        .unwrap_or_else(|| if args.release { "release" } else { "dev" }.into());
+    let profile = Profile::resolve(&profile_name, &root_manifest);
+    writeln!(rules, "extraargs = --cap-lints allow {}", profile.rustc_args())?;
     if args.verbose {
-        println!("==> Detected {} dependencies.", lockfile.packages.len());
+        println!("==> Profile: {} ({})", profile_name, profile.out_dir);
     }
 
+    let cargo_home = source::cargo_home()?;
+    let bin_dir = format!("build/{}", profile.out_dir);
+    let deps_dir = format!("{}/deps", bin_dir);
+    let host_deps_dir = if host_target.is_some() {
+        format!("{}/host-deps", bin_dir)
+    } else {
+        deps_dir.clone()
+    };
+
     let tree = lockfile.dependency_tree()?;
     let nodes = tree.nodes();
     let graph = tree.graph();
 
-    let (_, &root_idx) = nodes
-        .iter()
-        .find(|(dep, _)| dep.matches(root_package))
-        .unwrap();
-
-    let mut bfs = Bfs::new(&graph, root_idx);
-    while let Some(nx) = bfs.next(&graph) {
-        let node = &graph[nx];
-        let pkg_name = node.name.as_str();
-        let norm_pkg_name = normalize_crate_name(pkg_name);
-
-        // The main target we try to build.
-        if nx == root_idx {
-            build_rule(
-                &rules,
-                pkg_name,
-                &format!("build/{}", norm_pkg_name),
-                &["src/main.rs"],
-                &[&args.lockfile],
-                "build",
-                "bin",
-                "2018",
-                "dep-info,link",
-                &node.dependencies,
-            )?;
-
-            writeln!(rules, "default build/{}", norm_pkg_name)?;
-        } else {
-            // All the dependencies
-
-            if skip_dep(pkg_name) {
+    // Every selected member shares the same `build/deps` set: a crate
+    // named by more than one member's dependency graph is only compiled
+    // once, so its features have to be the union of what every member
+    // that reaches it asked for -- exactly like Cargo unifies features
+    // across a workspace. `manifests` persists across every member in the
+    // resolution pass below, so there a shared dependency's manifest is
+    // only loaded once; the emitting pass after it loads each emitted
+    // dependency's manifest again to find its crate directory.
+    let mut manifests = HashMap::new();
+    let mut member_root_idx = HashMap::new();
+    let mut resolved: HashMap<String, Resolved> = HashMap::new();
+
+    for member in &selected {
+        if let std::collections::hash_map::Entry::Vacant(entry) = manifests.entry(member.name.clone()) {
+            entry.insert(Manifest::from_path(&member.manifest_path)?);
+        }
+
+        let root_package = lockfile
+            .packages
+            .iter()
+            .find(|pkg| pkg.name.as_str() == member.name)
+            .ok_or_else(|| Error::new(format!("{} not found in {}", member.name, args.lockfile)))?;
+        let (_, &root_idx) = nodes
+            .iter()
+            .find(|(dep, _)| dep.matches(root_package))
+            .ok_or_else(|| Error::new(format!("{} missing from the dependency graph", member.name)))?;
+        member_root_idx.insert(member.name.clone(), root_idx);
+
+        // Load every reachable manifest up front so the feature resolver
+        // can see the whole `[features]`/dependency graph before any
+        // rule is written.
+        let mut bfs = Bfs::new(&graph, root_idx);
+        while let Some(nx) = bfs.next(&graph) {
+            let node = &graph[nx];
+            if nx == root_idx || manifests.contains_key(node.name.as_str()) {
                 continue;
             }
+            let (manifest, _, _) = load_dep_manifest(&cargo_home, &workspace_root, &root_manifest, node)?;
+            manifests.insert(node.name.as_str().to_string(), manifest);
+        }
 
-            let crate_path = Path::new(REGISTRY_PATH).join(&format!(
-                "{pkg}-{version}",
-                pkg = pkg_name,
-                version = node.version
-            ));
-            let toml_path = crate_path.join("Cargo.toml");
-            let mut f = File::open(&toml_path)?;
-            let mut buffer = Vec::new();
-            f.read_to_end(&mut buffer)?;
-            let manifest = Manifest::from_slice(&buffer)?;
-            let entry = manifest
-                .lib
-                .and_then(|lib| lib.path)
-                .unwrap_or_else(|| "src/lib.rs".into());
-            let entry_path = crate_path.join(entry);
-            let entry_path = entry_path.display().to_string();
-
-            build_rule(
-                &rules,
-                pkg_name,
-                &format!(
-                    "build/deps/lib{pkg}.rlib build/deps/lib{pkg}.rmeta",
-                    pkg = norm_pkg_name
-                ),
-                &[&entry_path],
-                &[],
-                "build/deps",
-                "lib",
-                &edition(manifest.package.unwrap().edition),
-                "dep-info,metadata,link",
-                &node.dependencies,
-            )?;
+        let member_resolved = features::resolve(
+            &member.name,
+            &manifests,
+            &target,
+            &args.features,
+            args.no_default_features,
+            args.all_features,
+        );
+        merge_resolved(&mut resolved, member_resolved);
+    }
+
+    let mut emitted = HashSet::new();
+
+    for member in &selected {
+        println!("==> Package: {}", member.name);
+
+        let root_idx = member_root_idx[&member.name];
+        let reachable = reachable_deps(&member.name, &resolved);
+        // Everything in `reachable` but not actually linked into the
+        // member itself only got there via a `[build-dependencies]` edge
+        // (e.g. `cc`, `bindgen`): it's a host-side build tool and has to be
+        // compiled for the host, never for `--target`.
+        let target_only = target_reachable_deps(&member.name, &resolved);
+
+        let mut bfs = Bfs::new(&graph, root_idx);
+        while let Some(nx) = bfs.next(&graph) {
+            let node = &graph[nx];
+            let pkg_name = node.name.as_str();
+            let norm_pkg_name = normalize_crate_name(pkg_name);
+            let empty = Resolved::default();
+            let own_resolved = resolved.get(pkg_name).unwrap_or(&empty);
+            let active_deps: Vec<&str> = node
+                .dependencies
+                .iter()
+                .map(|dep| dep.name.as_str())
+                .filter(|name| own_resolved.active_deps.contains(*name))
+                .collect();
+            let build_deps: Vec<&str> = node
+                .dependencies
+                .iter()
+                .map(|dep| dep.name.as_str())
+                .filter(|name| own_resolved.build_deps.contains(*name))
+                .collect();
+
+            // The member's own product.
+            if nx == root_idx {
+                let manifest = &manifests[&member.name];
+                let (crate_type, entry) = member_product(manifest, &member.dir);
+
+                let script = buildscript::detect(manifest, &member.dir);
+                let build_script = emit_build_script(
+                    &rules,
+                    &norm_pkg_name,
+                    script.as_deref(),
+                    &member.dir,
+                    &build_deps,
+                    "2018",
+                    &target,
+                    &host_deps_dir,
+                    &own_resolved.features,
+                    &translator,
+                    &profile,
+                )?;
+
+                if crate_type == "bin" {
+                    build_rule(
+                        &rules,
+                        pkg_name,
+                        &format!("{}/{}", bin_dir, norm_pkg_name),
+                        &[&entry],
+                        &[&args.lockfile],
+                        &bin_dir,
+                        &deps_dir,
+                        "bin",
+                        "2018",
+                        "dep-info,link",
+                        &active_deps,
+                        &own_resolved.features,
+                        &target,
+                        build_script.as_ref(),
+                    )?;
+                    writeln!(rules, "default {}/{}", bin_dir, norm_pkg_name)?;
+                } else {
+                    build_rule(
+                        &rules,
+                        pkg_name,
+                        &format!(
+                            "{deps}/lib{pkg}.rlib {deps}/lib{pkg}.rmeta",
+                            deps = deps_dir,
+                            pkg = norm_pkg_name
+                        ),
+                        &[&entry],
+                        &[],
+                        &deps_dir,
+                        &deps_dir,
+                        "lib",
+                        "2018",
+                        "dep-info,metadata,link",
+                        &active_deps,
+                        &own_resolved.features,
+                        &target,
+                        build_script.as_ref(),
+                    )?;
+                    writeln!(rules, "default {}/lib{}.rlib", deps_dir, norm_pkg_name)?;
+                }
+                emitted.insert(pkg_name.to_string());
+            } else {
+                // All the dependencies, minus the ones that aren't
+                // actually reachable under this target and feature
+                // selection (e.g. `winapi` when building for Linux), and
+                // the ones another member already emitted a rule for.
+                if !reachable.contains(pkg_name) || !emitted.insert(pkg_name.to_string()) {
+                    continue;
+                }
+
+                let (manifest, crate_dir, entry_path) =
+                    load_dep_manifest(&cargo_home, &workspace_root, &root_manifest, node)?;
+                let crate_edition = edition(*manifest.package.as_ref().unwrap().edition.get()?);
+
+                // A crate reached only through someone's `build.rs` (never
+                // linked into any member) is a host-side build tool: build
+                // it, and everything it in turn depends on, for the host
+                // running the build rather than for `--target`.
+                let is_host_only = !target_only.contains(pkg_name);
+                let (node_target, node_deps_dir) = if is_host_only {
+                    (host_target.as_ref().unwrap_or(&target), &host_deps_dir)
+                } else {
+                    (&target, &deps_dir)
+                };
+
+                let script = buildscript::detect(&manifest, &crate_dir);
+                let build_script = emit_build_script(
+                    &rules,
+                    &norm_pkg_name,
+                    script.as_deref(),
+                    &crate_dir,
+                    &build_deps,
+                    crate_edition,
+                    &target,
+                    &host_deps_dir,
+                    &own_resolved.features,
+                    &translator,
+                    &profile,
+                )?;
+
+                build_rule(
+                    &rules,
+                    pkg_name,
+                    &format!(
+                        "{deps}/lib{pkg}.rlib {deps}/lib{pkg}.rmeta",
+                        deps = node_deps_dir,
+                        pkg = norm_pkg_name
+                    ),
+                    &[&entry_path],
+                    &[],
+                    node_deps_dir,
+                    node_deps_dir,
+                    "lib",
+                    crate_edition,
+                    "dep-info,metadata,link",
+                    &active_deps,
+                    &own_resolved.features,
+                    node_target,
+                    build_script.as_ref(),
+                )?;
+            }
         }
     }
 
     Ok(())
 }
 
+/// Build the list of candidate workspace members: the `[workspace]`
+/// table's own members, plus the root package itself when the manifest
+/// declares both (Cargo treats the workspace root as an implicit member
+/// in that case). A plain, non-workspace manifest yields just its own
+/// package.
+fn workspace_members(
+    manifest_path: &str,
+    workspace_root: &Path,
+    manifest: &Manifest,
+) -> Result<Vec<Member>, Box<dyn std::error::Error>> {
+    let mut members = match &manifest.workspace {
+        Some(ws) => workspace::members(workspace_root, ws)?,
+        None => Vec::new(),
+    };
+
+    if let Some(package) = &manifest.package {
+        if !members.iter().any(|m| m.dir == workspace_root) {
+            members.insert(
+                0,
+                Member {
+                    name: package.name.clone(),
+                    manifest_path: PathBuf::from(manifest_path),
+                    dir: workspace_root.to_path_buf(),
+                },
+            );
+        }
+    } else if members.is_empty() {
+        return Err(Error::new("manifest has neither [package] nor [workspace]").into());
+    }
+
+    Ok(members)
+}
+
+/// Decide whether a workspace member's default product is a binary or a
+/// library, and where its entry point lives. A package with no `[lib]`
+/// and no `src/lib.rs` is bin-only, like `cargo new --bin`; everything
+/// else is built as a library, same as a dependency would be.
+fn member_product(manifest: &Manifest, dir: &Path) -> (&'static str, String) {
+    if let Some(lib) = &manifest.lib {
+        let path = lib.path.clone().unwrap_or_else(|| "src/lib.rs".into());
+        return ("lib", dir.join(path).display().to_string());
+    }
+    if manifest.bin.is_empty() && dir.join("src/lib.rs").is_file() {
+        return ("lib", dir.join("src/lib.rs").display().to_string());
+    }
+    let path = manifest
+        .bin
+        .first()
+        .and_then(|bin| bin.path.clone())
+        .unwrap_or_else(|| "src/main.rs".into());
+    ("bin", dir.join(path).display().to_string())
+}
+
+/// Fold one workspace member's feature resolution into the combined,
+/// whole-workspace one: a crate reached by more than one member gets the
+/// union of every feature/activation any of them asked for, since it's
+/// only ever compiled once and has to satisfy all of its callers at once.
+fn merge_resolved(combined: &mut HashMap<String, Resolved>, member: HashMap<String, Resolved>) {
+    for (name, r) in member {
+        let entry = combined.entry(name).or_default();
+        entry.features.extend(r.features);
+        entry.active_deps.extend(r.active_deps);
+        entry.build_deps.extend(r.build_deps);
+    }
+}
+
+/// Walk the resolved activation graph from the root package to find every
+/// crate name that's actually needed to build it under the selected target
+/// and features -- both regular dependencies and `[build-dependencies]`,
+/// since a crate that's reached only via a `build.rs` (like `cc` or
+/// `bindgen`) still needs its own `build_rule` emitted.
+fn reachable_deps(root_name: &str, resolved: &HashMap<String, Resolved>) -> HashSet<String> {
+    let mut seen = HashSet::new();
+    let mut queue = vec![root_name.to_string()];
+
+    while let Some(name) = queue.pop() {
+        if !seen.insert(name.clone()) {
+            continue;
+        }
+        if let Some(r) = resolved.get(&name) {
+            queue.extend(r.active_deps.iter().cloned());
+            queue.extend(r.build_deps.iter().cloned());
+        }
+    }
+
+    seen
+}
+
+/// Like [`reachable_deps`], but only follows `active_deps` edges -- the
+/// crates actually linked into the root package, which therefore have to
+/// be compiled for the requested `--target`. Anything in `reachable_deps`
+/// but missing from this set was pulled in purely through a `build.rs`
+/// dependency and belongs on the host instead.
+fn target_reachable_deps(root_name: &str, resolved: &HashMap<String, Resolved>) -> HashSet<String> {
+    let mut seen = HashSet::new();
+    let mut queue = vec![root_name.to_string()];
+
+    while let Some(name) = queue.pop() {
+        if !seen.insert(name.clone()) {
+            continue;
+        }
+        if let Some(r) = resolved.get(&name) {
+            queue.extend(r.active_deps.iter().cloned());
+        }
+    }
+
+    seen
+}
+
+/// Load a dependency's `Cargo.toml`, wherever its source put it on disk,
+/// and resolve its library entry point.
+///
+/// `Manifest::from_slice` never resolves `foo.workspace = true` fields, so
+/// a path/directory dependency -- which lives inside our own workspace and
+/// shares its `[workspace.package]` table -- needs `root_manifest` threaded
+/// through explicitly. A registry or git dependency either has no
+/// inherited fields at all, or (rare) is itself the root of its own
+/// on-disk workspace, which `complete_from_path_and_workspace` can still
+/// discover by walking up from its directory.
+fn load_dep_manifest(
+    cargo_home: &Path,
+    workspace_root: &Path,
+    root_manifest: &Manifest,
+    package: &cargo_lock::Package,
+) -> Result<(Manifest, PathBuf, String), Box<dyn std::error::Error>> {
+    let crate_path = source::locate(cargo_home, workspace_root, package)?;
+    let toml_path = crate_path.join("Cargo.toml");
+    let mut f = File::open(&toml_path)?;
+    let mut buffer = Vec::new();
+    f.read_to_end(&mut buffer)?;
+    let mut manifest = Manifest::from_slice(&buffer)?;
+
+    if crate_path.starts_with(workspace_root) {
+        manifest.complete_from_path_and_workspace(&toml_path, Some((root_manifest, workspace_root)))?;
+    } else {
+        manifest.complete_from_path_and_workspace(&toml_path, None)?;
+    }
+
+    let entry = manifest
+        .lib
+        .clone()
+        .and_then(|lib| lib.path)
+        .unwrap_or_else(|| "src/lib.rs".into());
+    let entry_path = crate_path.join(entry).display().to_string();
+
+    Ok((manifest, crate_path, entry_path))
+}
+
 fn build(args: Args) -> Result<(), Box<dyn std::error::Error>> {
     command(args.verbose, &["ninja", "-f", &args.ninja_file])?;
 
@@ -251,6 +610,43 @@ fn command(verbose: bool, cmdline: &[&str]) -> Result<(), Box<dyn std::error::Er
     Ok(())
 }
 
+/// Emit a crate's build-script steps, if it has one, returning the files
+/// its own `build_rule` call needs to hook into.
+#[allow(clippy::too_many_arguments)]
+fn emit_build_script<W: Write>(
+    mut out: W,
+    norm_pkg_name: &str,
+    script: Option<&Path>,
+    crate_dir: &Path,
+    build_deps: &[&str],
+    edition: &str,
+    target: &Target,
+    host_deps_dir: &str,
+    features: &BTreeSet<String>,
+    translator: &Path,
+    profile: &Profile,
+) -> Result<Option<buildscript::Outputs>, Box<dyn std::error::Error>> {
+    let script = match script {
+        Some(script) => script,
+        None => return Ok(None),
+    };
+    let outputs = buildscript::emit(
+        &mut out,
+        norm_pkg_name,
+        script,
+        crate_dir,
+        build_deps,
+        edition,
+        target,
+        host_deps_dir,
+        features,
+        translator,
+        profile,
+    )?;
+    Ok(Some(outputs))
+}
+
+#[allow(clippy::too_many_arguments)]
 fn build_rule<W: Write>(
     mut out: W,
     pkg_name: &str,
@@ -258,10 +654,14 @@ fn build_rule<W: Write>(
     deps: &[&str],
     implicit_deps: &[&str],
     outdir: &str,
+    deps_dir: &str,
     crate_type: &str,
     edition: &str,
     emit: &str,
-    dependencies: &[Dependency],
+    active_deps: &[&str],
+    features: &BTreeSet<String>,
+    platform: &Target,
+    build_script: Option<&buildscript::Outputs>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let norm_pkg_name = normalize_crate_name(pkg_name);
 
@@ -273,49 +673,53 @@ fn build_rule<W: Write>(
         implicit_deps.join(" ")
     )?;
 
-    for dep in dependencies {
-        if skip_dep(dep.name.as_str()) {
-            continue;
-        }
-        write!(
-            out,
-            "build/deps/lib{}.rlib ",
-            normalize_crate_name(dep.name.as_str())
-        )?;
+    for dep in active_deps {
+        write!(out, "{}/lib{}.rlib ", deps_dir, normalize_crate_name(dep))?;
+    }
+
+    if let Some(build_script) = build_script {
+        write!(out, "|| {} ", build_script.stamp)?;
     }
 
     writeln!(out)?;
     writeln!(out, "  name = {} ", norm_pkg_name)?;
     write!(
         out,
-        "  args = --crate-type {} --edition {} -L dependency=build/deps ",
-        crate_type, edition,
+        "  args = --crate-type {} --edition {} -L dependency={} ",
+        crate_type, edition, deps_dir,
     )?;
 
-    // We don't handle features yet,
-    // so let's hackily add some features to make libc compiled correctly.
-    if norm_pkg_name == "libc" {
-        write!(
-            out,
-            r#"--cfg 'feature="default"' --cfg 'feature="extra_traits"' --cfg 'feature="std"' --cfg freebsd11 --cfg libc_priv_mod_use --cfg libc_union --cfg libc_const_size_of --cfg libc_align --cfg libc_core_cvoid --cfg libc_packedN "#
-        )?;
+    for feature in features {
+        write!(out, r#"--cfg 'feature="{}"' "#, feature)?;
     }
 
-    for dep in dependencies {
-        if skip_dep(dep.name.as_str()) {
-            continue;
-        }
+    if !platform.is_host() {
+        write!(out, "--target {} ", platform.triple)?;
+    }
+
+    for dep in active_deps {
         write!(
             out,
-            "--extern {}=build/deps/lib{}.rlib ",
-            normalize_crate_name(dep.name.as_str()),
-            normalize_crate_name(dep.name.as_str())
+            "--extern {}={}/lib{}.rlib ",
+            normalize_crate_name(dep),
+            deps_dir,
+            normalize_crate_name(dep)
         )?;
     }
+
+    if let Some(build_script) = build_script {
+        write!(out, "$$(cat {} 2>/dev/null) ", build_script.args_file)?;
+    }
+
     writeln!(out)?;
     writeln!(out, "  outdir = {}", outdir)?;
     writeln!(out, "  emit = {}", emit)?;
     writeln!(out, "  depfile = {}/{}.d", outdir, norm_pkg_name)?;
+    writeln!(
+        out,
+        "  envfile = {}",
+        build_script.map(|b| b.env_file.as_str()).unwrap_or("/dev/null")
+    )?;
     writeln!(out)?;
 
     Ok(())
@@ -333,20 +737,24 @@ fn edition(ed: cargo_toml::Edition) -> &'static str {
     }
 }
 
-fn skip_dep(name: &str) -> bool {
-    // Skipping some crates we know we can't build
-    name.contains("winapi") || name.contains("redox")
-}
-
 fn usage() {
     const USAGE: &str = r#"
 USAGE:
     bygge [OPTIONS] [SUBCOMMAND]
 
 OPTIONS:
-    -p, --manifest-path  Path to Cargo.toml [default: Cargo.toml]
+        --manifest-path  Path to Cargo.toml [default: Cargo.toml]
     -l, --lockfile       Path to Cargo.lock [default: Cargo.lock]
     -n, --ninjafile      Path to build file [default: build.ninja]
+        --features       Comma-separated list of features to activate
+        --no-default-features
+                          Do not activate the root package's default feature
+        --all-features    Activate all available features of the root package
+        --target TRIPLE  Build for TRIPLE instead of the host [default: host]
+        --release        Build artifacts in release mode, with optimizations
+        --profile NAME   Build with the given profile [default: dev]
+    -p, --package NAME   Build only the named workspace member (repeatable)
+                          [default: the workspace's default/all members]
     -v, --verbose        Enable verbose output
     -h, --help           Print this help and exit.
     -V, --version        Print version info and exit
@@ -359,3 +767,71 @@ Available subcommands:
     println!("bygge v{}", env!("CARGO_PKG_VERSION"));
     println!("{}", USAGE);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolved(features: &[&str], active_deps: &[&str], build_deps: &[&str]) -> Resolved {
+        Resolved {
+            features: features.iter().map(|s| s.to_string()).collect(),
+            active_deps: active_deps.iter().map(|s| s.to_string()).collect(),
+            build_deps: build_deps.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    /// Regression test for `merge_resolved` overwriting a shared dependency's
+    /// resolution with whichever workspace member happened to be folded in
+    /// last, instead of unioning the two: a `serde` pulled in with `derive`
+    /// by one member and without it by another used to lose the `derive`
+    /// feature depending on iteration order.
+    #[test]
+    fn merge_resolved_unions_across_two_members() {
+        let mut combined = HashMap::new();
+        merge_resolved(
+            &mut combined,
+            HashMap::from([("serde".to_string(), resolved(&["derive"], &["serde_derive"], &[]))]),
+        );
+        merge_resolved(
+            &mut combined,
+            HashMap::from([("serde".to_string(), resolved(&["std"], &[], &["cc"]))]),
+        );
+
+        let serde = &combined["serde"];
+        assert_eq!(
+            serde.features,
+            BTreeSet::from(["derive".to_string(), "std".to_string()])
+        );
+        assert_eq!(serde.active_deps, BTreeSet::from(["serde_derive".to_string()]));
+        assert_eq!(serde.build_deps, BTreeSet::from(["cc".to_string()]));
+    }
+
+    #[test]
+    fn reachable_deps_follows_active_and_build_deps() {
+        let resolved = HashMap::from([
+            ("root".to_string(), resolved(&[], &["a"], &["cc"])),
+            ("a".to_string(), resolved(&[], &["b"], &[])),
+        ]);
+
+        let reachable = reachable_deps("root", &resolved);
+        assert_eq!(
+            reachable,
+            HashSet::from(["root".to_string(), "a".to_string(), "b".to_string(), "cc".to_string()])
+        );
+    }
+
+    /// `cc` is only reachable through `root`'s `build_deps` edge, so it must
+    /// be absent from the target-only set even though `reachable_deps`
+    /// includes it -- that's exactly the distinction the host/target split
+    /// in `create` relies on to keep build tools off `--target`.
+    #[test]
+    fn target_reachable_deps_excludes_build_only_deps() {
+        let resolved = HashMap::from([
+            ("root".to_string(), resolved(&[], &["a"], &["cc"])),
+            ("a".to_string(), resolved(&[], &["b"], &[])),
+        ]);
+
+        let target_only = target_reachable_deps("root", &resolved);
+        assert_eq!(target_only, HashSet::from(["root".to_string(), "a".to_string(), "b".to_string()]));
+    }
+}
@@ -0,0 +1,155 @@
+//! Target-platform evaluation for `[target.'cfg(...)'.dependencies]` and
+//! `[target.TRIPLE.dependencies]` tables.
+//!
+//! Cargo decides whether a platform-gated dependency applies by comparing
+//! its predicate against the set of `#[cfg(...)]` values the compiler
+//! reports for the chosen target. We get that set the same way Cargo does:
+//! by asking `rustc` directly, so we don't have to hand-maintain a table of
+//! `target_os`/`target_arch`/... for every triple ourselves.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    error::Error,
+    process::Command,
+    str::FromStr,
+};
+
+use cargo_platform::{Cfg, Platform};
+
+/// The `rustc --print cfg` output for one target triple.
+pub(crate) struct Target {
+    pub(crate) triple: String,
+    host_triple: String,
+    cfgs: Vec<Cfg>,
+}
+
+impl Target {
+    /// Detect the cfg set for `triple`, or the host triple if `None`.
+    pub(crate) fn detect(triple: Option<&str>) -> Result<Target, Box<dyn Error>> {
+        let host_triple = host_triple()?;
+        let triple = triple.map(str::to_string).unwrap_or_else(|| host_triple.clone());
+
+        let output = Command::new("rustc")
+            .args(["--print", "cfg", "--target", &triple])
+            .output()?;
+        if !output.status.success() {
+            return Err(format!("`rustc --print cfg --target {}` failed", triple).into());
+        }
+
+        let cfgs = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                line.parse::<Cfg>()
+                    .map_err(|e| format!("failed to parse `{}`: {}", line, e))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(Target {
+            triple,
+            host_triple,
+            cfgs,
+        })
+    }
+
+    /// Whether this target is the host rustc already defaults to, in which
+    /// case `--target` doesn't need to be passed to `rustc` at all.
+    pub(crate) fn is_host(&self) -> bool {
+        self.triple == self.host_triple
+    }
+
+    /// Evaluate a `[target.PREDICATE.dependencies]` key: either a bare
+    /// target triple (e.g. `x86_64-pc-windows-msvc`) or a `cfg(...)`
+    /// expression (`all()`/`any()`/`not()`/key-value/bare flags).
+    pub(crate) fn matches(&self, predicate: &str) -> bool {
+        match Platform::from_str(predicate) {
+            Ok(platform) => platform.matches(&self.triple, &self.cfgs),
+            Err(_) => false,
+        }
+    }
+
+    /// The host triple, regardless of which target this `Target` describes.
+    pub(crate) fn host_triple(&self) -> &str {
+        &self.host_triple
+    }
+
+    /// The `CARGO_CFG_*` environment variables Cargo sets when invoking a
+    /// build script, as a string of space-separated `KEY=value` words.
+    /// Keys that appear more than once (`target_feature`, mainly) collapse
+    /// into one comma-joined value, matching Cargo's own behaviour.
+    pub(crate) fn cargo_cfg_env(&self) -> String {
+        let mut bare = BTreeSet::new();
+        let mut keyed: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for cfg in &self.cfgs {
+            match cfg {
+                Cfg::Name(name) => {
+                    bare.insert(name.clone());
+                }
+                Cfg::KeyPair(key, value) => keyed.entry(key.clone()).or_default().push(value.clone()),
+            }
+        }
+
+        let mut words = Vec::new();
+        for name in bare {
+            words.push(format!("CARGO_CFG_{}=", name.to_uppercase()));
+        }
+        for (key, values) in keyed {
+            words.push(format!("CARGO_CFG_{}={}", key.to_uppercase(), values.join(",")));
+        }
+        words.join(" ")
+    }
+}
+
+fn host_triple() -> Result<String, Box<dyn Error>> {
+    let output = Command::new("rustc").arg("-vV").output()?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("host: "))
+        .map(str::to_string)
+        .ok_or_else(|| "could not determine host triple from `rustc -vV`".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(cfgs: &[&str]) -> Target {
+        Target {
+            triple: "x86_64-unknown-linux-gnu".into(),
+            host_triple: "x86_64-unknown-linux-gnu".into(),
+            cfgs: cfgs.iter().map(|c| c.parse().unwrap()).collect(),
+        }
+    }
+
+    #[test]
+    fn matches_bare_target_triple() {
+        let target = target(&["unix", "target_os = \"linux\""]);
+        assert!(target.matches("x86_64-unknown-linux-gnu"));
+        assert!(!target.matches("x86_64-pc-windows-msvc"));
+    }
+
+    #[test]
+    fn matches_cfg_predicate() {
+        let target = target(&["unix", "target_os = \"linux\""]);
+        assert!(target.matches("cfg(unix)"));
+        assert!(target.matches("cfg(target_os = \"linux\")"));
+        assert!(!target.matches("cfg(windows)"));
+    }
+
+    #[test]
+    fn matches_compound_cfg_predicate() {
+        let target = target(&["unix", "target_os = \"linux\""]);
+        assert!(target.matches("cfg(all(unix, not(windows)))"));
+        assert!(target.matches("cfg(any(windows, target_os = \"linux\"))"));
+        assert!(!target.matches("cfg(all(unix, windows))"));
+    }
+
+    #[test]
+    fn cargo_cfg_env_collapses_repeated_keys() {
+        let target = target(&["unix", "target_feature = \"sse\"", "target_feature = \"sse2\""]);
+        assert_eq!(
+            target.cargo_cfg_env(),
+            "CARGO_CFG_UNIX= CARGO_CFG_TARGET_FEATURE=sse,sse2"
+        );
+    }
+}
@@ -0,0 +1,210 @@
+//! Build profile resolution (`dev`/`release`/custom), mirroring Cargo's
+//! `[profile.*]` tables.
+//!
+//! `bygge` used to bake a single fixed `--cap-lints allow -C debuginfo=2`
+//! string into every `rustc` invocation, with no way to ask for an
+//! optimized build. This module resolves the actual profile Cargo would
+//! use -- built-in defaults for `dev`/`release`, overridden by whatever
+//! the root `Cargo.toml`'s `[profile.*]` tables set -- into the handful
+//! of codegen flags every `rustc` invocation gets, plus the profile-specific
+//! output directory so dev and release builds don't clobber each other.
+
+use cargo_toml::{DebugSetting, LtoSetting, Manifest, Profile as ManifestProfile, StripSetting, Value};
+
+/// Resolved codegen settings for one profile, merged with its Cargo
+/// defaults and any `[profile.*]` override from the root manifest.
+#[derive(Debug, Clone)]
+pub(crate) struct Profile {
+    /// Directory segment artifacts are written under: `build/debug/...`
+    /// for `dev`, `build/release/...` for `release`, `build/<name>/...`
+    /// for anything else -- matching Cargo's own `target/<dir-name>`.
+    pub(crate) out_dir: String,
+    opt_level: String,
+    debuginfo: DebugSetting,
+    lto: LtoSetting,
+    codegen_units: Option<u16>,
+    panic: Option<String>,
+    overflow_checks: bool,
+    debug_assertions: bool,
+    strip: StripSetting,
+}
+
+impl Profile {
+    /// Resolve `name` ("dev", "release", or a custom `[profile.NAME]")
+    /// against Cargo's built-in defaults, then apply the root manifest's
+    /// override for that profile, if any.
+    pub(crate) fn resolve(name: &str, manifest: &Manifest) -> Profile {
+        let custom = manifest.profile.custom.get(name);
+        let named = match name {
+            "dev" => manifest.profile.dev.as_ref(),
+            "release" => manifest.profile.release.as_ref(),
+            _ => None,
+        };
+        let over = named.or(custom);
+
+        // A custom profile bases itself on `dev` or `release` per its own
+        // `inherits` key (mandatory for custom profiles in real Cargo; we
+        // default to `dev` rather than erroring if it's missing).
+        let base = match name {
+            "dev" | "release" => name,
+            _ => match over.and_then(|o| o.inherits.as_deref()) {
+                Some("release") => "release",
+                _ => "dev",
+            },
+        };
+
+        let mut profile = Profile::builtin(base);
+        if name != "dev" && name != "release" {
+            profile.out_dir = name.into();
+        }
+        if let Some(over) = over {
+            profile.apply(over);
+        }
+
+        profile
+    }
+
+    /// Cargo's hardcoded defaults for the two built-in profiles.
+    fn builtin(name: &str) -> Profile {
+        match name {
+            "release" => Profile {
+                out_dir: "release".into(),
+                opt_level: "3".into(),
+                debuginfo: DebugSetting::None,
+                lto: LtoSetting::None,
+                codegen_units: Some(16),
+                panic: None,
+                overflow_checks: false,
+                debug_assertions: false,
+                strip: StripSetting::None,
+            },
+            _ => Profile {
+                out_dir: "debug".into(),
+                opt_level: "0".into(),
+                debuginfo: DebugSetting::Full,
+                lto: LtoSetting::None,
+                codegen_units: Some(256),
+                panic: None,
+                overflow_checks: true,
+                debug_assertions: true,
+                strip: StripSetting::None,
+            },
+        }
+    }
+
+    fn apply(&mut self, over: &ManifestProfile) {
+        if let Some(opt_level) = &over.opt_level {
+            self.opt_level = opt_level_str(opt_level);
+        }
+        if let Some(debug) = &over.debug {
+            self.debuginfo = debug.clone();
+        }
+        if let Some(lto) = &over.lto {
+            self.lto = lto.clone();
+        }
+        if let Some(codegen_units) = over.codegen_units {
+            self.codegen_units = Some(codegen_units);
+        }
+        if let Some(panic) = &over.panic {
+            self.panic = Some(panic.clone());
+        }
+        if let Some(overflow_checks) = over.overflow_checks {
+            self.overflow_checks = overflow_checks;
+        }
+        if let Some(debug_assertions) = over.debug_assertions {
+            self.debug_assertions = debug_assertions;
+        }
+        if let Some(strip) = &over.strip {
+            self.strip = strip.clone();
+        }
+    }
+
+    /// The `-C`/codegen flags this profile contributes to `rustc`, in the
+    /// form `create` splices straight into the ninja file's global
+    /// `extraargs` line.
+    pub(crate) fn rustc_args(&self) -> String {
+        let mut args = format!(
+            "-C opt-level={} -C debuginfo={} -C overflow-checks={} -C debug-assertions={}",
+            self.opt_level,
+            self.debuginfo.clone() as u8,
+            self.overflow_checks,
+            self.debug_assertions,
+        );
+
+        if let Some(codegen_units) = self.codegen_units {
+            args.push_str(&format!(" -C codegen-units={}", codegen_units));
+        }
+        if let Some(panic) = &self.panic {
+            args.push_str(&format!(" -C panic={}", panic));
+        }
+        match self.lto {
+            LtoSetting::None | LtoSetting::ThinLocal => {}
+            LtoSetting::Thin => args.push_str(" -C lto=thin"),
+            LtoSetting::Fat => args.push_str(" -C lto"),
+        }
+        match self.strip {
+            StripSetting::None => {}
+            StripSetting::Debuginfo => args.push_str(" -C strip=debuginfo"),
+            StripSetting::Symbols => args.push_str(" -C strip=symbols"),
+        }
+
+        args
+    }
+}
+
+fn opt_level_str(value: &Value) -> String {
+    match value {
+        Value::Integer(n) => n.to_string(),
+        Value::String(s) => s.clone(),
+        Value::Boolean(b) => if *b { "1" } else { "0" }.into(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(toml: &str) -> Manifest {
+        Manifest::from_slice(toml.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn dev_defaults_are_unoptimized_and_checked() {
+        let profile = Profile::resolve("dev", &manifest(""));
+        assert_eq!(profile.out_dir, "debug");
+        assert_eq!(profile.rustc_args(), "-C opt-level=0 -C debuginfo=2 -C overflow-checks=true -C debug-assertions=true -C codegen-units=256");
+    }
+
+    /// Regression test for a custom profile always basing itself on `dev`
+    /// regardless of its own `inherits` key: `[profile.bench]` with
+    /// `inherits = "release"` used to still get `dev`'s debug-assertions
+    /// and `-O0` instead of `release`'s settings.
+    #[test]
+    fn custom_profile_honors_inherits_release() {
+        let root = manifest(
+            r#"
+            [profile.bench]
+            inherits = "release"
+            debug-assertions = true
+            "#,
+        );
+        let profile = Profile::resolve("bench", &root);
+        assert_eq!(profile.out_dir, "bench");
+        // Picked up from `release`'s defaults, not overridden.
+        assert_eq!(profile.rustc_args(), "-C opt-level=3 -C debuginfo=0 -C overflow-checks=false -C debug-assertions=true -C codegen-units=16");
+    }
+
+    #[test]
+    fn custom_profile_defaults_to_dev_without_inherits() {
+        let root = manifest(
+            r#"
+            [profile.custom]
+            opt-level = 1
+            "#,
+        );
+        let profile = Profile::resolve("custom", &root);
+        assert_eq!(profile.out_dir, "custom");
+        assert_eq!(profile.rustc_args(), "-C opt-level=1 -C debuginfo=2 -C overflow-checks=true -C debug-assertions=true -C codegen-units=256");
+    }
+}
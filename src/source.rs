@@ -0,0 +1,289 @@
+//! Locating a dependency's checked-out sources on disk.
+//!
+//! `cargo fetch` populates `$CARGO_HOME` with everything a lockfile needs,
+//! but where exactly a given package ends up depends on what kind of
+//! source it came from: a registry crate lands under
+//! `registry/src/<registry-ident>/<name>-<version>`, a git dependency under
+//! `git/checkouts/<repo-name>-<ident>/<commit>`, and a path dependency
+//! isn't fetched anywhere at all -- it's already part of the workspace.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use cargo_lock::{package::SourceKind, Package};
+
+/// `$CARGO_HOME`, or `~/.cargo` if the environment variable isn't set.
+pub(crate) fn cargo_home() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    if let Ok(dir) = env::var("CARGO_HOME") {
+        return Ok(PathBuf::from(dir));
+    }
+
+    let home = env::var("HOME").map_err(|_| "neither $CARGO_HOME nor $HOME is set")?;
+    Ok(Path::new(&home).join(".cargo"))
+}
+
+/// Find the directory containing `package`'s `Cargo.toml`.
+pub(crate) fn locate(
+    cargo_home: &Path,
+    workspace_root: &Path,
+    package: &Package,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let source = match &package.source {
+        Some(source) => source,
+        // No source means it's a path dependency: Cargo.lock never records
+        // the path itself, so look for it inside the workspace.
+        None => return find_by_name(workspace_root, package.name.as_str(), 4),
+    };
+
+    match source.kind() {
+        SourceKind::Registry | SourceKind::SparseRegistry | SourceKind::LocalRegistry => {
+            registry_crate_dir(cargo_home, source.url().as_str(), package)
+        }
+        SourceKind::Git(_) => git_crate_dir(cargo_home, source, package),
+        SourceKind::Path | SourceKind::Directory => {
+            find_by_name(workspace_root, package.name.as_str(), 4)
+        }
+        // `SourceKind` is `#[non_exhaustive]`: fall back to a workspace
+        // search for any kind we don't know how to fetch from `$CARGO_HOME`.
+        _ => find_by_name(workspace_root, package.name.as_str(), 4),
+    }
+}
+
+fn registry_crate_dir(
+    cargo_home: &Path,
+    _registry_url: &str,
+    package: &Package,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let src_root = cargo_home.join("registry").join("src");
+    let want = format!("{}-{}", package.name, package.version);
+
+    for entry in fs::read_dir(&src_root).map_err(|e| {
+        format!(
+            "could not read registry source directory `{}`: {}",
+            src_root.display(),
+            e
+        )
+    })? {
+        let registry_dir = entry?.path();
+        if !registry_dir.is_dir() {
+            continue;
+        }
+        let crate_dir = registry_dir.join(&want);
+        if crate_dir.is_dir() {
+            return Ok(crate_dir);
+        }
+    }
+
+    Err(format!(
+        "could not find `{}` in any registry under `{}`",
+        want,
+        src_root.display()
+    )
+    .into())
+}
+
+fn git_crate_dir(
+    cargo_home: &Path,
+    source: &cargo_lock::SourceId,
+    package: &Package,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let checkouts_root = cargo_home.join("git").join("checkouts");
+    let repo_name = source
+        .url()
+        .path_segments()
+        .and_then(Iterator::last)
+        .unwrap_or_default()
+        .trim_end_matches(".git");
+    let rev = source.precise();
+
+    for entry in fs::read_dir(&checkouts_root).map_err(|e| {
+        format!(
+            "could not read git checkouts directory `{}`: {}",
+            checkouts_root.display(),
+            e
+        )
+    })? {
+        let ident_dir = entry?.path();
+        let ident_name = ident_dir
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default();
+        // The real directory name is `<repo-name>-<hash>`; we don't
+        // replicate Cargo's internal hash, so match on the repo name
+        // prefix instead.
+        if !ident_name.starts_with(repo_name) {
+            continue;
+        }
+
+        for commit in fs::read_dir(&ident_dir)? {
+            let commit_dir = commit?.path();
+            let commit_name = commit_dir
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or_default();
+            if let Some(rev) = rev {
+                if !rev.starts_with(commit_name) && !commit_name.starts_with(rev) {
+                    continue;
+                }
+            }
+            if let Ok(dir) = find_by_name(&commit_dir, package.name.as_str(), 3) {
+                return Ok(dir);
+            }
+        }
+    }
+
+    Err(format!(
+        "could not find a checkout for `{}` (repo `{}`) under `{}`",
+        package.name,
+        repo_name,
+        checkouts_root.display()
+    )
+    .into())
+}
+
+/// Search under `root` (up to `depth` levels deep, skipping `target`/`.git`)
+/// for a directory whose `Cargo.toml` declares package `name`.
+fn find_by_name(
+    root: &Path,
+    name: &str,
+    depth: u32,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let toml_path = root.join("Cargo.toml");
+    if toml_path.is_file() {
+        if let Ok(manifest) = cargo_toml::Manifest::from_path(&toml_path) {
+            if let Some(package) = &manifest.package {
+                if package.name == name {
+                    return Ok(root.to_path_buf());
+                }
+            }
+        }
+    }
+
+    if depth > 0 {
+        for entry in fs::read_dir(root)? {
+            let path = entry?.path();
+            let dir_name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+            if path.is_dir() && dir_name != "target" && dir_name != ".git" {
+                if let Ok(found) = find_by_name(&path, name, depth - 1) {
+                    return Ok(found);
+                }
+            }
+        }
+    }
+
+    Err(format!("could not find package `{}` under `{}`", name, root.display()).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// A scratch directory under the system temp dir, removed on drop.
+    struct ScratchDir(PathBuf);
+
+    impl ScratchDir {
+        fn new(label: &str) -> Self {
+            static COUNTER: AtomicU32 = AtomicU32::new(0);
+            let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+            let path = env::temp_dir().join(format!("bygge-source-test-{}-{}-{}", label, std::process::id(), n));
+            fs::create_dir_all(&path).unwrap();
+            ScratchDir(path)
+        }
+    }
+
+    impl Drop for ScratchDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn package(name: &str, version: &str, source: Option<&str>) -> Package {
+        Package {
+            name: name.parse().unwrap(),
+            version: version.parse().unwrap(),
+            source: source.map(|s| s.parse().unwrap()),
+            checksum: None,
+            dependencies: Vec::new(),
+            replace: None,
+        }
+    }
+
+    #[test]
+    fn registry_crate_dir_finds_sparse_registry_packages() {
+        let cargo_home = ScratchDir::new("sparse-registry");
+        let registry_dir = cargo_home
+            .0
+            .join("registry")
+            .join("src")
+            .join("index.crates.io-1234567890abcdef");
+        fs::create_dir_all(registry_dir.join("serde-1.0.0")).unwrap();
+
+        let pkg = package(
+            "serde",
+            "1.0.0",
+            Some("sparse+https://index.crates.io/"),
+        );
+        let dir = locate(&cargo_home.0, Path::new("/nonexistent"), &pkg).unwrap();
+        assert_eq!(dir, registry_dir.join("serde-1.0.0"));
+    }
+
+    #[test]
+    fn registry_crate_dir_errors_when_package_is_missing() {
+        let cargo_home = ScratchDir::new("sparse-registry-missing");
+        let registry_dir = cargo_home
+            .0
+            .join("registry")
+            .join("src")
+            .join("index.crates.io-1234567890abcdef");
+        fs::create_dir_all(&registry_dir).unwrap();
+
+        let pkg = package("serde", "1.0.0", Some("sparse+https://index.crates.io/"));
+        assert!(locate(&cargo_home.0, Path::new("/nonexistent"), &pkg).is_err());
+    }
+
+    #[test]
+    fn git_crate_dir_finds_checkout_by_repo_name_and_commit_prefix() {
+        let cargo_home = ScratchDir::new("git-checkout");
+        let commit_dir = cargo_home
+            .0
+            .join("git")
+            .join("checkouts")
+            .join("libssh2-static-sys-abcdef0123456789")
+            .join("80e71a3021618eb05656c58fb7c5ef5f12bc747f");
+        fs::create_dir_all(&commit_dir).unwrap();
+        fs::write(
+            commit_dir.join("Cargo.toml"),
+            "[package]\nname = \"libssh2-static-sys\"\nversion = \"0.1.0\"\n",
+        )
+        .unwrap();
+
+        let pkg = package(
+            "libssh2-static-sys",
+            "0.1.0",
+            Some(
+                "git+https://github.com/alexcrichton/libssh2-static-sys\
+                 #80e71a3021618eb05656c58fb7c5ef5f12bc747f",
+            ),
+        );
+        let dir = locate(&cargo_home.0, Path::new("/nonexistent"), &pkg).unwrap();
+        assert_eq!(dir, commit_dir);
+    }
+
+    #[test]
+    fn find_by_name_descends_into_workspace_members() {
+        let workspace = ScratchDir::new("workspace-member");
+        let member_dir = workspace.0.join("crates").join("foo");
+        fs::create_dir_all(&member_dir).unwrap();
+        fs::write(
+            member_dir.join("Cargo.toml"),
+            "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+        )
+        .unwrap();
+
+        let dir = find_by_name(&workspace.0, "foo", 4).unwrap();
+        assert_eq!(dir, member_dir);
+    }
+}
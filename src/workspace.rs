@@ -0,0 +1,197 @@
+//! Cargo workspace member discovery and selection.
+//!
+//! `create` used to call `manifest.package.unwrap()` and resolve a single
+//! root package, so it panicked on a virtual manifest (a `[workspace]`
+//! with no `[package]`) and had no way to see more than one crate. This
+//! module expands a workspace's `members`/`exclude` path patterns into
+//! the actual member directories, and picks which of them to build.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use cargo_toml::{Manifest, Workspace};
+
+/// One member crate of a workspace: its package name, and where its
+/// `Cargo.toml` and crate root live.
+#[derive(Debug, Clone)]
+pub(crate) struct Member {
+    pub(crate) name: String,
+    pub(crate) manifest_path: PathBuf,
+    pub(crate) dir: PathBuf,
+}
+
+/// Expand `workspace.members`, minus `workspace.exclude`, into the member
+/// crates they name, relative to `workspace_root`.
+///
+/// Cargo's glob syntax is supported only for the common `dir/*` shape (one
+/// level of wildcard directories); anything else is treated as a literal
+/// path, which covers the overwhelming majority of real workspaces.
+pub(crate) fn members(
+    workspace_root: &Path,
+    workspace: &Workspace,
+) -> Result<Vec<Member>, Box<dyn std::error::Error>> {
+    let mut excluded = Vec::new();
+    for pattern in &workspace.exclude {
+        excluded.extend(expand(workspace_root, pattern)?);
+    }
+
+    let mut dirs = Vec::new();
+    for pattern in &workspace.members {
+        for dir in expand(workspace_root, pattern)? {
+            if !excluded.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+    }
+
+    dirs.into_iter()
+        .map(|dir| {
+            let manifest_path = dir.join("Cargo.toml");
+            let name = Manifest::from_path(&manifest_path)?
+                .package
+                .ok_or_else(|| format!("workspace member {} has no [package]", dir.display()))?
+                .name;
+            Ok(Member { name, manifest_path, dir })
+        })
+        .collect()
+}
+
+/// Pick the members to actually build: an explicit `-p`/`--package`
+/// selection wins, then the workspace's own `default-members`, then
+/// every member.
+pub(crate) fn select<'m>(
+    members: &'m [Member],
+    workspace_root: &Path,
+    requested: &[String],
+    default_members: &[String],
+) -> Result<Vec<&'m Member>, Box<dyn std::error::Error>> {
+    if !requested.is_empty() {
+        return requested
+            .iter()
+            .map(|name| {
+                members
+                    .iter()
+                    .find(|m| &m.name == name)
+                    .ok_or_else(|| format!("workspace has no member named `{}`", name).into())
+            })
+            .collect();
+    }
+
+    if !default_members.is_empty() {
+        let mut selected = Vec::new();
+        for pattern in default_members {
+            for dir in expand(workspace_root, pattern)? {
+                if let Some(member) = members.iter().find(|m| m.dir == dir) {
+                    selected.push(member);
+                }
+            }
+        }
+        return Ok(selected);
+    }
+
+    Ok(members.iter().collect())
+}
+
+/// Expand one `members`/`exclude`/`default-members` entry: a literal
+/// directory, or a `prefix/*` glob over that directory's immediate
+/// children that themselves look like crates.
+fn expand(workspace_root: &Path, pattern: &str) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    match pattern.strip_suffix("/*") {
+        Some(prefix) => {
+            let base = workspace_root.join(prefix);
+            let mut dirs = Vec::new();
+            for entry in fs::read_dir(&base)? {
+                let path = entry?.path();
+                if path.is_dir() && path.join("Cargo.toml").is_file() {
+                    dirs.push(path);
+                }
+            }
+            dirs.sort();
+            Ok(dirs)
+        }
+        None => Ok(vec![workspace_root.join(pattern)]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// A scratch workspace directory under the system temp dir, removed on
+    /// drop.
+    struct ScratchDir(PathBuf);
+
+    impl ScratchDir {
+        fn new(label: &str) -> Self {
+            static COUNTER: AtomicU32 = AtomicU32::new(0);
+            let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+            let path = std::env::temp_dir().join(format!(
+                "bygge-workspace-test-{}-{}-{}",
+                label,
+                std::process::id(),
+                n
+            ));
+            fs::create_dir_all(&path).unwrap();
+            ScratchDir(path)
+        }
+
+        fn crate_at(&self, rel: &str, name: &str) {
+            let dir = self.0.join(rel);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(
+                dir.join("Cargo.toml"),
+                format!("[package]\nname = \"{}\"\nversion = \"0.1.0\"\n", name),
+            )
+            .unwrap();
+        }
+    }
+
+    impl Drop for ScratchDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Regression test for a `workspace.exclude` glob (e.g. `examples/*`)
+    /// being joined onto `workspace_root` literally instead of expanded:
+    /// it never matched the expanded member directories and excluded
+    /// nothing.
+    #[test]
+    fn members_honors_globbed_exclude() {
+        let root = ScratchDir::new("globbed-exclude");
+        root.crate_at("examples/ex1", "ex1");
+        root.crate_at("examples/ex2", "ex2");
+        root.crate_at("crates/core", "core");
+
+        let workspace = Workspace {
+            members: vec!["crates/core".to_string(), "examples/*".to_string()],
+            exclude: vec!["examples/*".to_string()],
+            ..Default::default()
+        };
+
+        let members = members(&root.0, &workspace).unwrap();
+        let names: BTreeSet<_> = members.iter().map(|m| m.name.clone()).collect();
+        assert_eq!(names, BTreeSet::from(["core".to_string()]));
+    }
+
+    #[test]
+    fn members_keeps_literal_exclude_entries() {
+        let root = ScratchDir::new("literal-exclude");
+        root.crate_at("a", "a");
+        root.crate_at("b", "b");
+
+        let workspace = Workspace {
+            members: vec!["a".to_string(), "b".to_string()],
+            exclude: vec!["b".to_string()],
+            ..Default::default()
+        };
+
+        let members = members(&root.0, &workspace).unwrap();
+        let names: BTreeSet<_> = members.iter().map(|m| m.name.clone()).collect();
+        assert_eq!(names, BTreeSet::from(["a".to_string()]));
+    }
+}